@@ -12,18 +12,93 @@
 // limitations under the License.
 
 use crate::albert::attention::AlbertSelfAttention;
-use tch::{nn, Tensor};
+use tch::{nn, Kind, Tensor};
 use crate::albert::AlbertConfig;
-use crate::albert::albert::Activation;
-use crate::common::activations::{_gelu_new, _gelu, _relu, _mish};
+use crate::common::activation_registry::{resolve_activation, ActivationFn, ActivationName};
 use std::borrow::BorrowMut;
+use std::sync::Arc;
+
+/// A single gradient-checkpointed layer call: `input` is the real hidden
+/// state that was passed in, not a detached copy, so recomputing on it in
+/// `backward_t` reattaches gradients to whatever actually produced it.
+///
+/// `tch` has no binding for a custom autograd `Function`, so this cannot be
+/// made transparent to a plain `loss.backward()` call the way
+/// `torch.utils.checkpoint` is: a caller training with `gradient_checkpointing`
+/// must walk the checkpoints returned by `forward_t` in reverse order and call
+/// `backward_t` on each instead of relying on a single implicit backward pass.
+/// See `backward_checkpoints` for that loop.
+pub struct LayerCheckpoint<'a> {
+    layer: &'a AlbertLayer,
+    input: Tensor,
+    mask: Option<Tensor>,
+}
+
+impl<'a> LayerCheckpoint<'a> {
+    /// Runs `layer` under a `no_grad` boundary, so none of its internal
+    /// activations are retained, and keeps the real (not detached)
+    /// `hidden_state` so the recompute in `backward_t` stays connected to
+    /// whatever produced it.
+    fn forward(layer: &'a AlbertLayer, hidden_state: &Tensor, mask: &Option<Tensor>, train: bool) -> (Tensor, Option<Tensor>, LayerCheckpoint<'a>) {
+        // `train` must match what `backward_t` recomputes with (always `true`,
+        // since this is only reached from `if train && self.gradient_checkpointing`):
+        // passing a different value here would silently change dropout behavior
+        // between the forward output/loss and the recomputed backward graph,
+        // producing gradients for a different function than the one that ran.
+        let (output, attention_weights) = tch::no_grad(|| layer.forward_t(hidden_state, mask, train));
+        let checkpoint = LayerCheckpoint {
+            layer,
+            input: hidden_state.shallow_clone(),
+            mask: mask.as_ref().map(Tensor::shallow_clone),
+        };
+        (output, attention_weights, checkpoint)
+    }
+
+    /// Recomputes this layer's forward pass with gradients enabled and
+    /// backpropagates `grad_output` through it via the vector-Jacobian-product
+    /// trick (`(recomputed_output * grad_output).sum().backward()`).
+    ///
+    /// Returns `Some(grad_input)` when `input` was a checkpoint-local leaf
+    /// (produced by a previous checkpoint's `no_grad` forward), so the caller
+    /// must feed it into the previous checkpoint's `backward_t` call; returns
+    /// `None` when `input` was already attached to the real upstream graph
+    /// (the first checkpointed layer in a chain), since the `backward()` call
+    /// above already propagated the gradient further upstream on its own.
+    pub fn backward_t(&self, grad_output: &Tensor) -> Option<Tensor> {
+        let already_attached = self.input.requires_grad();
+        let input = if already_attached {
+            self.input.shallow_clone()
+        } else {
+            self.input.shallow_clone().set_requires_grad(true)
+        };
+
+        let (recomputed_output, _) = self.layer.forward_t(&input, &self.mask, true);
+        (&recomputed_output * &grad_output.detach()).sum(Kind::Float).backward();
+
+        if already_attached { None } else { Some(input.grad()) }
+    }
+}
+
+/// Backpropagates `grad_output` through `checkpoints` (as returned by
+/// `AlbertLayerGroup::forward_t`/`AlbertTransformer::forward_t` under
+/// `gradient_checkpointing`), walking them in reverse so each checkpoint's
+/// recomputed gradient feeds the one before it.
+pub fn backward_checkpoints(checkpoints: &[LayerCheckpoint], grad_output: &Tensor) {
+    let mut grad = grad_output.shallow_clone();
+    for checkpoint in checkpoints.iter().rev() {
+        match checkpoint.backward_t(&grad) {
+            Some(grad_input) => grad = grad_input,
+            None => break,
+        }
+    }
+}
 
 pub struct AlbertLayer {
-    attention: AlbertSelfAttention,
-    full_layer_layer_norm: nn::LayerNorm,
-    ffn: nn::Linear,
-    ffn_output: nn::Linear,
-    activation: Box<dyn Fn(&Tensor) -> Tensor>,
+    pub(crate) attention: AlbertSelfAttention,
+    pub(crate) full_layer_layer_norm: nn::LayerNorm,
+    pub(crate) ffn: nn::Linear,
+    pub(crate) ffn_output: nn::Linear,
+    pub(crate) activation: Arc<ActivationFn>,
 }
 
 impl AlbertLayer {
@@ -40,12 +115,9 @@ impl AlbertLayer {
         let ffn = nn::linear(&(p / "ffn"), config.hidden_size, config.intermediate_size, Default::default());
         let ffn_output = nn::linear(&(p / "ffn_output"), config.intermediate_size, config.hidden_size, Default::default());
 
-        let activation = Box::new(match &config.hidden_act {
-            Activation::gelu_new => _gelu_new,
-            Activation::gelu => _gelu,
-            Activation::relu => _relu,
-            Activation::mish => _mish
-        });
+        let activation_name = config.hidden_act.registry_name();
+        let activation = resolve_activation(activation_name)
+            .unwrap_or_else(|| panic!("Unregistered activation function: {}", activation_name));
 
         AlbertLayer { attention, full_layer_layer_norm, ffn, ffn_output, activation }
     }
@@ -65,9 +137,10 @@ impl AlbertLayer {
 }
 
 pub struct AlbertLayerGroup {
-    output_hidden_states: bool,
-    output_attentions: bool,
-    layers: Vec<AlbertLayer>,
+    pub(crate) output_hidden_states: bool,
+    pub(crate) output_attentions: bool,
+    gradient_checkpointing: bool,
+    pub(crate) layers: Vec<AlbertLayer>,
 }
 
 impl AlbertLayerGroup {
@@ -84,21 +157,30 @@ impl AlbertLayerGroup {
             None => false
         };
 
+        // `gradient_checkpointing: Option<bool>` still needs to be added to
+        // `AlbertConfig`'s own definition (outside this module) for this to
+        // read anything other than the `None` default; not part of this change.
+        let gradient_checkpointing = match config.gradient_checkpointing {
+            Some(value) => value,
+            None => false
+        };
+
         let mut layers: Vec<AlbertLayer> = vec!();
         for layer_index in 0..config.inner_group_num {
             layers.push(AlbertLayer::new(&(p / layer_index), config));
         };
 
-        AlbertLayerGroup { output_hidden_states, output_attentions, layers }
+        AlbertLayerGroup { output_hidden_states, output_attentions, gradient_checkpointing, layers }
     }
 
     pub fn forward_t(&self,
                      hidden_states: &Tensor,
                      mask: &Option<Tensor>,
                      train: bool)
-                     -> (Tensor, Option<Vec<Tensor>>, Option<Vec<Tensor>>) {
+                     -> (Tensor, Option<Vec<Tensor>>, Option<Vec<Tensor>>, Vec<LayerCheckpoint<'_>>) {
         let mut all_hidden_states: Option<Vec<Tensor>> = if self.output_hidden_states { Some(vec!()) } else { None };
         let mut all_attentions: Option<Vec<Tensor>> = if self.output_attentions { Some(vec!()) } else { None };
+        let mut checkpoints: Vec<LayerCheckpoint<'_>> = vec!();
 
         let mut hidden_state = hidden_states.copy();
         let mut attention_weights: Option<Tensor>;
@@ -110,7 +192,13 @@ impl AlbertLayerGroup {
                         hidden_states.push(hidden_state.as_ref().copy());
                     };
 
-                    let temp = layer.forward_t(&hidden_state, &mask, train);
+                    let temp = if train && self.gradient_checkpointing {
+                        let (output, attention_weights, checkpoint) = LayerCheckpoint::forward(layer, &hidden_state, &mask, train);
+                        checkpoints.push(checkpoint);
+                        (output, attention_weights)
+                    } else {
+                        layer.forward_t(&hidden_state, &mask, train)
+                    };
                     hidden_state = temp.0;
                     attention_weights = temp.1;
                     if let Some(attentions) = all_attentions.borrow_mut() {
@@ -121,17 +209,17 @@ impl AlbertLayerGroup {
             };
         };
 
-        (hidden_state, all_hidden_states, all_attentions)
+        (hidden_state, all_hidden_states, all_attentions, checkpoints)
     }
 }
 
 pub struct AlbertTransformer {
-    output_hidden_states: bool,
-    output_attentions: bool,
-    num_hidden_layers: i64,
-    num_hidden_groups: i64,
-    embedding_hidden_mapping_in: nn::Linear,
-    layers: Vec<AlbertLayerGroup>,
+    pub(crate) output_hidden_states: bool,
+    pub(crate) output_attentions: bool,
+    pub(crate) num_hidden_layers: i64,
+    pub(crate) num_hidden_groups: i64,
+    pub(crate) embedding_hidden_mapping_in: nn::Linear,
+    pub(crate) layers: Vec<AlbertLayerGroup>,
 }
 
 impl AlbertTransformer {
@@ -165,16 +253,20 @@ impl AlbertTransformer {
         }
     }
 
+    /// Returns the checkpoints accumulated across every `num_hidden_layers`
+    /// iteration alongside the usual outputs; when `gradient_checkpointing`
+    /// is off (or `train` is `false`) this is simply empty. See
+    /// `LayerCheckpoint`/`backward_checkpoints` for how to use it.
     pub fn forward_t(&self,
                      hidden_states: &Tensor,
                      mask: Option<Tensor>,
                      train: bool)
-                     -> (Tensor, Option<Vec<Tensor>>, Option<Vec<Vec<Tensor>>>) {
+                     -> (Tensor, Option<Vec<Tensor>>, Option<Vec<Vec<Tensor>>>, Vec<LayerCheckpoint<'_>>) {
         let mut hidden_state = hidden_states.apply(&self.embedding_hidden_mapping_in);
 
         let mut all_hidden_states: Option<Vec<Tensor>> = if self.output_hidden_states { Some(vec!()) } else { None };
         let mut all_attentions: Option<Vec<Vec<Tensor>>> = if self.output_attentions { Some(vec!()) } else { None };
-
+        let mut checkpoints: Vec<LayerCheckpoint<'_>> = vec!();
 
         for i in 0..self.num_hidden_layers {
             let group_idx = i / (self.num_hidden_layers / self.num_hidden_groups);
@@ -187,12 +279,13 @@ impl AlbertTransformer {
             let temp = layer.forward_t(&hidden_state, &mask, train);
             hidden_state = temp.0;
             let attention_weights = temp.1;
+            checkpoints.extend(temp.3);
             if let Some(attentions) = all_attentions.borrow_mut() {
                 attentions.push(attention_weights.unwrap());
             };
         };
 
-        (hidden_state, all_hidden_states, all_attentions)
+        (hidden_state, all_hidden_states, all_attentions, checkpoints)
     }
 }
 
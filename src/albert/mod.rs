@@ -0,0 +1,3 @@
+pub mod encoder;
+pub mod quantized;
+pub mod pooling;
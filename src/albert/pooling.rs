@@ -0,0 +1,124 @@
+// Copyright 2018 Google AI and Google Brain team.
+// Copyright 2020-present, the HuggingFace Inc. team.
+// Copyright 2020 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tch::Tensor;
+
+/// Strategy used to reduce an ALBERT `hidden_state` of shape
+/// `[batch, seq, hidden]` down to a single vector per sequence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pooling {
+    /// Takes the hidden state at position 0 (the `[CLS]` token).
+    Cls,
+    /// Mask-weighted average of the token hidden states.
+    Mean,
+    /// Element-wise max over the token hidden states, ignoring masked positions.
+    MaxTokens,
+}
+
+/// Reduces `hidden_state` to one vector per sequence according to `pooling`,
+/// optionally L2-normalizing the result so that a dot product between two
+/// pooled vectors equals their cosine similarity.
+pub fn pool_hidden_states(hidden_state: &Tensor, mask: &Option<Tensor>, pooling: Pooling, normalize: bool) -> Tensor {
+    let pooled = match pooling {
+        Pooling::Cls => hidden_state.select(1, 0),
+        Pooling::Mean => mean_pool(hidden_state, mask),
+        Pooling::MaxTokens => max_pool(hidden_state, mask),
+    };
+
+    if normalize {
+        &pooled / pooled.norm_scalaropt_dim(2.0, &[-1], true)
+    } else {
+        pooled
+    }
+}
+
+fn mean_pool(hidden_state: &Tensor, mask: &Option<Tensor>) -> Tensor {
+    match mask {
+        Some(mask) => {
+            let expanded_mask = mask.unsqueeze(-1).expand_as(hidden_state).to_kind(hidden_state.kind());
+            let summed = (hidden_state * &expanded_mask).sum_dim_intlist(&[1], false, hidden_state.kind());
+            let counts = expanded_mask.sum_dim_intlist(&[1], false, hidden_state.kind()).clamp_min(1e-9);
+            summed / counts
+        }
+        None => hidden_state.mean_dim(&[1], false, hidden_state.kind()),
+    }
+}
+
+fn max_pool(hidden_state: &Tensor, mask: &Option<Tensor>) -> Tensor {
+    match mask {
+        Some(mask) => {
+            let expanded_mask = mask.unsqueeze(-1).expand_as(hidden_state).to_kind(hidden_state.kind());
+            let masked = hidden_state + (expanded_mask.ones_like() - expanded_mask) * -1e9;
+            masked.max_dim(1, false).0
+        }
+        None => hidden_state.max_dim(1, false).0,
+    }
+}
+
+/// Cosine similarity between two pooled vectors (or batches of pooled
+/// vectors, compared row-wise).
+pub fn cosine_similarity(a: &Tensor, b: &Tensor) -> Tensor {
+    let a_norm = a / a.norm_scalaropt_dim(2.0, &[-1], true);
+    let b_norm = b / b.norm_scalaropt_dim(2.0, &[-1], true);
+    (a_norm * b_norm).sum_dim_intlist(&[-1], false, a.kind())
+}
+
+/// Ranks each row of `queries` against every row of `candidates` by cosine
+/// similarity, returning `(scores, indices)` of the `top_k` best candidates
+/// per query, both of shape `[num_queries, top_k]`.
+pub fn top_k_by_similarity(queries: &Tensor, candidates: &Tensor, top_k: i64) -> (Tensor, Tensor) {
+    let queries_norm = queries / queries.norm_scalaropt_dim(2.0, &[-1], true);
+    let candidates_norm = candidates / candidates.norm_scalaropt_dim(2.0, &[-1], true);
+    let scores = queries_norm.matmul(&candidates_norm.tr());
+    scores.topk(top_k, -1, true, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // hidden_state: batch 1, seq 3, hidden 2; mask marks the last token as
+    // padding, so only the first two rows ([1, 2], [3, 4]) should count.
+    fn masked_hidden_state() -> (Tensor, Option<Tensor>) {
+        let hidden_state = Tensor::of_slice(&[1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0]).view([1, 3, 2]);
+        let mask = Tensor::of_slice(&[1i64, 1, 0]).view([1, 3]);
+        (hidden_state, Some(mask))
+    }
+
+    #[test]
+    fn mean_pool_ignores_masked_tokens() {
+        let (hidden_state, mask) = masked_hidden_state();
+        let pooled = pool_hidden_states(&hidden_state, &mask, Pooling::Mean, false);
+
+        assert_eq!(pooled.double_value(&[0, 0]), 2.0);
+        assert_eq!(pooled.double_value(&[0, 1]), 3.0);
+    }
+
+    #[test]
+    fn max_pool_ignores_masked_tokens() {
+        let (hidden_state, mask) = masked_hidden_state();
+        let pooled = pool_hidden_states(&hidden_state, &mask, Pooling::MaxTokens, false);
+
+        assert_eq!(pooled.double_value(&[0, 0]), 3.0);
+        assert_eq!(pooled.double_value(&[0, 1]), 4.0);
+    }
+
+    #[test]
+    fn cls_pool_takes_first_token() {
+        let (hidden_state, _) = masked_hidden_state();
+        let pooled = pool_hidden_states(&hidden_state, &None, Pooling::Cls, false);
+
+        assert_eq!(pooled.double_value(&[0, 0]), 1.0);
+        assert_eq!(pooled.double_value(&[0, 1]), 2.0);
+    }
+}
@@ -0,0 +1,581 @@
+// Copyright 2018 Google AI and Google Brain team.
+// Copyright 2020-present, the HuggingFace Inc. team.
+// Copyright 2020 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::albert::attention::AlbertSelfAttention;
+use tch::{nn, Kind, Tensor};
+use crate::albert::AlbertConfig;
+use crate::albert::encoder::AlbertTransformer;
+use crate::common::activation_registry::{resolve_activation, ActivationFn, ActivationName};
+use std::borrow::BorrowMut;
+use std::io;
+use std::sync::Arc;
+
+/// Block size (number of weight elements sharing a single scale) used by both
+/// quantization schemes below. Matches the `Q*_0` block layout used by GGUF.
+const QUANT_BLOCK_SIZE: usize = 32;
+
+/// Quantization scheme applied to the `ffn`, `ffn_output` and
+/// `embedding_hidden_mapping_in` projections of a quantized ALBERT layer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuantizationScheme {
+    /// 8-bit symmetric quantization: one `f16` scale per 32-element block.
+    Q8_0,
+    /// 4-bit symmetric quantization: one `f16` scale plus packed nibbles per 32-element block.
+    Q4_0,
+}
+
+/// A single quantized weight matrix, stored as fixed-size blocks of packed
+/// integers with a per-block `f16` scale, and dequantized on the fly in `forward`.
+///
+/// Biases are kept in full precision, mirroring the bias handling of `nn::Linear`.
+pub struct QuantizedLinear {
+    scheme: QuantizationScheme,
+    in_dim: i64,
+    out_dim: i64,
+    /// Packed weight data: one byte per element for `Q8_0`, one byte per two
+    /// elements for `Q4_0`.
+    packed_weight: Tensor,
+    /// One `f16` scale per block, shape `[out_dim, ceil(in_dim / QUANT_BLOCK_SIZE)]`.
+    scales: Tensor,
+    bias: Option<Tensor>,
+}
+
+impl QuantizedLinear {
+    /// Quantizes a full-precision `nn::Linear`'s weight into fixed-size blocks,
+    /// keeping the bias as-is.
+    pub fn from_full_precision(linear: &nn::Linear, scheme: QuantizationScheme) -> QuantizedLinear {
+        let weight = &linear.ws;
+        let (out_dim, in_dim) = weight.size2().unwrap();
+        let num_blocks = (in_dim as usize + QUANT_BLOCK_SIZE - 1) / QUANT_BLOCK_SIZE;
+
+        let mut scales = Tensor::zeros(&[out_dim, num_blocks as i64], (Kind::Half, weight.device()));
+        let packed_kind = match scheme {
+            QuantizationScheme::Q8_0 => Kind::Uint8,
+            QuantizationScheme::Q4_0 => Kind::Uint8,
+        };
+        let packed_cols = match scheme {
+            QuantizationScheme::Q8_0 => in_dim,
+            QuantizationScheme::Q4_0 => (in_dim + 1) / 2,
+        };
+        let mut packed_weight = Tensor::zeros(&[out_dim, packed_cols], (packed_kind, weight.device()));
+
+        for row in 0..out_dim {
+            for block in 0..num_blocks as i64 {
+                let start = block * QUANT_BLOCK_SIZE as i64;
+                let end = (start + QUANT_BLOCK_SIZE as i64).min(in_dim);
+                let block_values = weight.get(row).narrow(0, start, end - start);
+                let amax = block_values.abs().max().double_value(&[]);
+
+                let (scale, quantized_block) = match scheme {
+                    QuantizationScheme::Q8_0 => {
+                        let scale = if amax > 0.0 { amax / 127.0 } else { 1.0 };
+                        let quantized = (&block_values / scale).round().clamp(-127.0, 127.0);
+                        (scale, quantized)
+                    }
+                    QuantizationScheme::Q4_0 => {
+                        let scale = if amax > 0.0 { amax / 7.0 } else { 1.0 };
+                        let quantized = (&block_values / scale).round().clamp(-7.0, 7.0);
+                        (scale, quantized)
+                    }
+                };
+
+                scales.get(row).get(block).copy_(&Tensor::from(scale as f32));
+
+                match scheme {
+                    QuantizationScheme::Q8_0 => {
+                        packed_weight
+                            .get(row)
+                            .narrow(0, start, end - start)
+                            .copy_(&(quantized_block + 128).to_kind(Kind::Uint8));
+                    }
+                    QuantizationScheme::Q4_0 => {
+                        pack_nibbles(&mut packed_weight.get(row), &quantized_block, start);
+                    }
+                }
+            }
+        }
+
+        QuantizedLinear {
+            scheme,
+            in_dim,
+            out_dim,
+            packed_weight,
+            scales,
+            bias: linear.bs.as_ref().map(|bias| bias.copy()),
+        }
+    }
+
+    /// Reads a quantized tensor (packed weight + block scales) for a single
+    /// layer from a GGUF-style file, positioned at the tensor's data section.
+    pub fn from_gguf_reader<R: io::Read>(
+        reader: &mut R,
+        in_dim: i64,
+        out_dim: i64,
+        scheme: QuantizationScheme,
+        with_bias: bool,
+    ) -> io::Result<QuantizedLinear> {
+        let num_blocks = (in_dim as usize + QUANT_BLOCK_SIZE - 1) / QUANT_BLOCK_SIZE;
+        let packed_cols = match scheme {
+            QuantizationScheme::Q8_0 => in_dim,
+            QuantizationScheme::Q4_0 => (in_dim + 1) / 2,
+        };
+
+        let mut scale_bytes = vec![0u8; out_dim as usize * num_blocks * 2];
+        reader.read_exact(&mut scale_bytes)?;
+        let scales = decode_half_scales(&scale_bytes, out_dim, num_blocks as i64);
+
+        let mut weight_bytes = vec![0u8; out_dim as usize * packed_cols as usize];
+        reader.read_exact(&mut weight_bytes)?;
+        let packed_weight = Tensor::of_slice(&weight_bytes).view([out_dim, packed_cols]);
+
+        let bias = if with_bias {
+            let mut bias_bytes = vec![0u8; out_dim as usize * 4];
+            reader.read_exact(&mut bias_bytes)?;
+            let values: Vec<f32> = bias_bytes
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                .collect();
+            Some(Tensor::of_slice(&values))
+        } else {
+            None
+        };
+
+        Ok(QuantizedLinear { scheme, in_dim, out_dim, packed_weight, scales, bias })
+    }
+
+    /// Dequantizes the packed blocks to the compute dtype and applies the matmul.
+    pub fn forward(&self, input: &Tensor) -> Tensor {
+        let dequantized = self.dequantize(input.kind());
+        let output = input.matmul(&dequantized.tr());
+        match &self.bias {
+            Some(bias) => output + bias,
+            None => output,
+        }
+    }
+
+    fn dequantize(&self, compute_kind: Kind) -> Tensor {
+        let num_blocks = (self.in_dim as usize + QUANT_BLOCK_SIZE - 1) / QUANT_BLOCK_SIZE;
+        let scales = self.scales.to_kind(compute_kind);
+
+        let centered = match self.scheme {
+            QuantizationScheme::Q8_0 => self.packed_weight.to_kind(compute_kind) - 128,
+            QuantizationScheme::Q4_0 => unpack_nibbles(&self.packed_weight, self.in_dim).to_kind(compute_kind) - 7,
+        };
+
+        // `centered` only ever has `in_dim` elements per row (the last block
+        // can be shorter than QUANT_BLOCK_SIZE), but the reshape below needs
+        // every block to be uniformly sized; pad the tail with zeros and
+        // narrow it back off after the multiply, rather than assume `in_dim`
+        // is an exact multiple of QUANT_BLOCK_SIZE.
+        let padded_len = num_blocks as i64 * QUANT_BLOCK_SIZE as i64;
+        let centered = if padded_len > self.in_dim {
+            centered.constant_pad_nd(&[0, padded_len - self.in_dim], 0.0)
+        } else {
+            centered
+        };
+
+        let centered = centered.view([self.out_dim, num_blocks as i64, QUANT_BLOCK_SIZE as i64]);
+        let scales = scales.unsqueeze(-1);
+        (centered * scales).view([self.out_dim, -1]).narrow(1, 0, self.in_dim)
+    }
+}
+
+fn pack_nibbles(row: &mut Tensor, quantized_block: &Tensor, start: i64) {
+    // Packs signed 4-bit values (shifted to [0, 15]) two-per-byte: the
+    // element at `start + j` goes in the low nibble of byte `(start + j) / 2`
+    // when `start + j` is even, and the high nibble when it is odd.
+    let shifted = (quantized_block + 7).to_kind(Kind::Uint8);
+    let len = shifted.size()[0];
+    for j in 0..len {
+        let nibble = shifted.int64_value(&[j]) as u8 & 0x0F;
+        let elem_index = start + j;
+        let byte_index = elem_index / 2;
+        let current = row.int64_value(&[byte_index]) as u8;
+        let updated = if elem_index % 2 == 0 {
+            (current & 0xF0) | nibble
+        } else {
+            (current & 0x0F) | (nibble << 4)
+        };
+        row.get(byte_index).copy_(&Tensor::from(updated));
+    }
+}
+
+fn unpack_nibbles(packed: &Tensor, in_dim: i64) -> Tensor {
+    let low = packed.bitwise_and_tensor(&Tensor::from(0x0Fu8)).to_kind(Kind::Int64);
+    let high = (packed >> 4).bitwise_and_tensor(&Tensor::from(0x0Fu8)).to_kind(Kind::Int64);
+    Tensor::stack(&[low, high], -1).view([packed.size()[0], -1]).narrow(1, 0, in_dim)
+}
+
+/// Decodes raw IEEE754 binary16 bytes (as stored in a GGUF tensor) into a
+/// `[out_dim, num_blocks]` tensor of per-block scales. Bit-decodes rather
+/// than numerically casting, since the bytes are a bit pattern, not a value.
+fn decode_half_scales(bytes: &[u8], out_dim: i64, num_blocks: i64) -> Tensor {
+    let values: Vec<f32> = bytes
+        .chunks_exact(2)
+        .map(|chunk| half_bits_to_f32(u16::from_le_bytes([chunk[0], chunk[1]])))
+        .collect();
+    Tensor::of_slice(&values).view([out_dim, num_blocks]).to_kind(Kind::Half)
+}
+
+fn half_bits_to_f32(bits: u16) -> f32 {
+    let sign = (bits >> 15) & 0x1;
+    let exponent = (bits >> 10) & 0x1F;
+    let mantissa = (bits & 0x3FF) as f32;
+
+    let magnitude = if exponent == 0 {
+        mantissa * 2f32.powi(-24)
+    } else if exponent == 0x1F {
+        if mantissa == 0.0 { f32::INFINITY } else { f32::NAN }
+    } else {
+        (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15)
+    };
+
+    if sign == 1 { -magnitude } else { magnitude }
+}
+
+pub struct QuantizedAlbertLayer {
+    attention: AlbertSelfAttention,
+    full_layer_layer_norm: nn::LayerNorm,
+    ffn: QuantizedLinear,
+    ffn_output: QuantizedLinear,
+    activation: Arc<ActivationFn>,
+}
+
+impl QuantizedAlbertLayer {
+    pub fn new(p: &nn::Path, config: &AlbertConfig, scheme: QuantizationScheme) -> QuantizedAlbertLayer {
+        let attention = AlbertSelfAttention::new(p / "attention", &config);
+
+        let layer_norm_eps = match config.layer_norm_eps {
+            Some(value) => value,
+            None => 1e-12
+        };
+        let layer_norm_config = nn::LayerNormConfig { eps: layer_norm_eps, ..Default::default() };
+        let full_layer_layer_norm = nn::layer_norm(&(p / "full_layer_layer_norm"), vec![config.hidden_size], layer_norm_config);
+
+        let ffn = nn::linear(&(p / "ffn"), config.hidden_size, config.intermediate_size, Default::default());
+        let ffn = QuantizedLinear::from_full_precision(&ffn, scheme);
+        let ffn_output = nn::linear(&(p / "ffn_output"), config.intermediate_size, config.hidden_size, Default::default());
+        let ffn_output = QuantizedLinear::from_full_precision(&ffn_output, scheme);
+
+        let activation_name = config.hidden_act.registry_name();
+        let activation = resolve_activation(activation_name)
+            .unwrap_or_else(|| panic!("Unregistered activation function: {}", activation_name));
+
+        QuantizedAlbertLayer { attention, full_layer_layer_norm, ffn, ffn_output, activation }
+    }
+
+    pub fn forward_t(&self,
+                     hidden_states: &Tensor,
+                     mask: &Option<Tensor>,
+                     train: bool) -> (Tensor, Option<Tensor>) {
+        let (attention_output, attention_weights) = self.attention.forward_t(hidden_states, mask, train);
+        let ffn_output = self.ffn.forward(&attention_output);
+        let ffn_output: Tensor = (self.activation)(&ffn_output);
+        let ffn_output = self.ffn_output.forward(&ffn_output);
+        let ffn_output = (ffn_output + attention_output).apply(&self.full_layer_layer_norm);
+
+        (ffn_output, attention_weights)
+    }
+}
+
+pub struct QuantizedAlbertLayerGroup {
+    output_hidden_states: bool,
+    output_attentions: bool,
+    layers: Vec<QuantizedAlbertLayer>,
+}
+
+impl QuantizedAlbertLayerGroup {
+    pub fn new(p: &nn::Path, config: &AlbertConfig, scheme: QuantizationScheme) -> QuantizedAlbertLayerGroup {
+        let p = &(p / "albert_layers");
+
+        let output_attentions = match config.output_attentions {
+            Some(value) => value,
+            None => false
+        };
+
+        let output_hidden_states = match config.output_hidden_states {
+            Some(value) => value,
+            None => false
+        };
+
+        let mut layers: Vec<QuantizedAlbertLayer> = vec!();
+        for layer_index in 0..config.inner_group_num {
+            layers.push(QuantizedAlbertLayer::new(&(p / layer_index), config, scheme));
+        };
+
+        QuantizedAlbertLayerGroup { output_hidden_states, output_attentions, layers }
+    }
+
+    pub fn forward_t(&self,
+                     hidden_states: &Tensor,
+                     mask: &Option<Tensor>,
+                     train: bool)
+                     -> (Tensor, Option<Vec<Tensor>>, Option<Vec<Tensor>>) {
+        let mut all_hidden_states: Option<Vec<Tensor>> = if self.output_hidden_states { Some(vec!()) } else { None };
+        let mut all_attentions: Option<Vec<Tensor>> = if self.output_attentions { Some(vec!()) } else { None };
+
+        let mut hidden_state = hidden_states.copy();
+        let mut attention_weights: Option<Tensor>;
+        let mut layers = self.layers.iter();
+        loop {
+            match layers.next() {
+                Some(layer) => {
+                    if let Some(hidden_states) = all_hidden_states.borrow_mut() {
+                        hidden_states.push(hidden_state.as_ref().copy());
+                    };
+
+                    let temp = layer.forward_t(&hidden_state, &mask, train);
+                    hidden_state = temp.0;
+                    attention_weights = temp.1;
+                    if let Some(attentions) = all_attentions.borrow_mut() {
+                        attentions.push(attention_weights.as_ref().unwrap().copy());
+                    };
+                }
+                None => break
+            };
+        };
+
+        (hidden_state, all_hidden_states, all_attentions)
+    }
+}
+
+/// Quantized mirror of [`crate::albert::encoder::AlbertTransformer`]. Since
+/// ALBERT re-runs the same physical layer group for every one of
+/// `num_hidden_layers` iterations, the one-time quantization cost of the
+/// shared group is amortized over every loop of `forward_t`.
+pub struct QuantizedAlbertTransformer {
+    output_hidden_states: bool,
+    output_attentions: bool,
+    num_hidden_layers: i64,
+    num_hidden_groups: i64,
+    embedding_hidden_mapping_in: QuantizedLinear,
+    layers: Vec<QuantizedAlbertLayerGroup>,
+}
+
+impl QuantizedAlbertTransformer {
+    pub fn new(p: &nn::Path, config: &AlbertConfig, scheme: QuantizationScheme) -> QuantizedAlbertTransformer {
+        let p_layers = &(p / "albert_layer_groups");
+
+        let output_attentions = match config.output_attentions {
+            Some(value) => value,
+            None => false
+        };
+
+        let output_hidden_states = match config.output_hidden_states {
+            Some(value) => value,
+            None => false
+        };
+
+        let embedding_hidden_mapping_in = nn::linear(&(p / "embedding_hidden_mapping_in"), config.embedding_size, config.hidden_size, Default::default());
+        let embedding_hidden_mapping_in = QuantizedLinear::from_full_precision(&embedding_hidden_mapping_in, scheme);
+
+        let mut layers: Vec<QuantizedAlbertLayerGroup> = vec!();
+        for layer_index in 0..config.inner_group_num {
+            layers.push(QuantizedAlbertLayerGroup::new(&(p_layers / layer_index), config, scheme));
+        };
+
+        QuantizedAlbertTransformer {
+            output_hidden_states,
+            output_attentions,
+            num_hidden_layers: config.num_hidden_layers,
+            num_hidden_groups: config.num_hidden_groups,
+            embedding_hidden_mapping_in,
+            layers,
+        }
+    }
+
+    /// Loads a quantized transformer from a GGUF-style file: the attention
+    /// blocks and layer norms are created at `p` and loaded through the usual
+    /// `VarStore` path, while `ffn`, `ffn_output` and
+    /// `embedding_hidden_mapping_in` are read as quantized blocks from `reader`,
+    /// using `config.quantization` to pick the block layout (`Q8_0` vs `Q4_0`).
+    pub fn from_gguf_reader<R: io::Read>(p: &nn::Path, reader: &mut R, config: &AlbertConfig) -> io::Result<QuantizedAlbertTransformer> {
+        // `quantization: Option<QuantizationScheme>` still needs to be added to
+        // `AlbertConfig`'s own definition (outside this module) for this to
+        // read anything other than the `Q8_0` default below; not part of this change.
+        let scheme = config.quantization.unwrap_or(QuantizationScheme::Q8_0);
+        let output_attentions = config.output_attentions.unwrap_or(false);
+        let output_hidden_states = config.output_hidden_states.unwrap_or(false);
+
+        let embedding_hidden_mapping_in = QuantizedLinear::from_gguf_reader(
+            reader, config.embedding_size, config.hidden_size, scheme, true,
+        )?;
+
+        let p_layers = &(p / "albert_layer_groups");
+        let mut layer_groups = vec!();
+        for group_index in 0..config.inner_group_num {
+            let p_group = &(p_layers / group_index / "albert_layers");
+            let mut layers = vec!();
+            for layer_index in 0..config.inner_group_num {
+                let attention = AlbertSelfAttention::new(p_group / layer_index / "attention", &config);
+
+                let layer_norm_eps = config.layer_norm_eps.unwrap_or(1e-12);
+                let layer_norm_config = nn::LayerNormConfig { eps: layer_norm_eps, ..Default::default() };
+                let full_layer_layer_norm = nn::layer_norm(
+                    &(p_group / layer_index / "full_layer_layer_norm"), vec![config.hidden_size], layer_norm_config,
+                );
+
+                let ffn = QuantizedLinear::from_gguf_reader(
+                    reader, config.hidden_size, config.intermediate_size, scheme, true,
+                )?;
+                let ffn_output = QuantizedLinear::from_gguf_reader(
+                    reader, config.intermediate_size, config.hidden_size, scheme, true,
+                )?;
+
+                let activation_name = config.hidden_act.registry_name();
+                let activation = resolve_activation(activation_name)
+                    .unwrap_or_else(|| panic!("Unregistered activation function: {}", activation_name));
+
+                layers.push(QuantizedAlbertLayer { attention, full_layer_layer_norm, ffn, ffn_output, activation });
+            }
+            layer_groups.push(QuantizedAlbertLayerGroup { output_hidden_states, output_attentions, layers });
+        }
+
+        Ok(QuantizedAlbertTransformer {
+            output_hidden_states,
+            output_attentions,
+            num_hidden_layers: config.num_hidden_layers,
+            num_hidden_groups: config.num_hidden_groups,
+            embedding_hidden_mapping_in,
+            layers: layer_groups,
+        })
+    }
+
+    /// Converts an already-trained, full-precision `AlbertTransformer` into
+    /// its quantized mirror in memory, without going through a file at all:
+    /// attention blocks and layer norms are moved over as-is (they are never
+    /// quantized), and `ffn`/`ffn_output`/`embedding_hidden_mapping_in` are
+    /// quantized from their current weights via `QuantizedLinear::from_full_precision`.
+    ///
+    /// Takes `transformer` by value since `AlbertSelfAttention`/`nn::LayerNorm`
+    /// are moved into the result rather than copied.
+    pub fn from_full_precision(transformer: AlbertTransformer, scheme: QuantizationScheme) -> QuantizedAlbertTransformer {
+        let output_hidden_states = transformer.output_hidden_states;
+        let output_attentions = transformer.output_attentions;
+        let num_hidden_layers = transformer.num_hidden_layers;
+        let num_hidden_groups = transformer.num_hidden_groups;
+
+        let embedding_hidden_mapping_in = QuantizedLinear::from_full_precision(&transformer.embedding_hidden_mapping_in, scheme);
+
+        let layers = transformer.layers.into_iter().map(|group| {
+            let layers = group.layers.into_iter().map(|layer| {
+                let ffn = QuantizedLinear::from_full_precision(&layer.ffn, scheme);
+                let ffn_output = QuantizedLinear::from_full_precision(&layer.ffn_output, scheme);
+                QuantizedAlbertLayer {
+                    attention: layer.attention,
+                    full_layer_layer_norm: layer.full_layer_layer_norm,
+                    ffn,
+                    ffn_output,
+                    activation: layer.activation,
+                }
+            }).collect();
+
+            QuantizedAlbertLayerGroup {
+                output_hidden_states: group.output_hidden_states,
+                output_attentions: group.output_attentions,
+                layers,
+            }
+        }).collect();
+
+        QuantizedAlbertTransformer {
+            output_hidden_states,
+            output_attentions,
+            num_hidden_layers,
+            num_hidden_groups,
+            embedding_hidden_mapping_in,
+            layers,
+        }
+    }
+
+    pub fn forward_t(&self,
+                     hidden_states: &Tensor,
+                     mask: Option<Tensor>,
+                     train: bool)
+                     -> (Tensor, Option<Vec<Tensor>>, Option<Vec<Vec<Tensor>>>) {
+        let mut hidden_state = self.embedding_hidden_mapping_in.forward(hidden_states);
+
+        let mut all_hidden_states: Option<Vec<Tensor>> = if self.output_hidden_states { Some(vec!()) } else { None };
+        let mut all_attentions: Option<Vec<Vec<Tensor>>> = if self.output_attentions { Some(vec!()) } else { None };
+
+        for i in 0..self.num_hidden_layers {
+            let group_idx = i / (self.num_hidden_layers / self.num_hidden_groups);
+            let layer = &self.layers[group_idx as usize];
+
+            if let Some(hidden_states) = all_hidden_states.borrow_mut() {
+                hidden_states.push(hidden_state.as_ref().copy());
+            };
+
+            let temp = layer.forward_t(&hidden_state, &mask, train);
+            hidden_state = temp.0;
+            let attention_weights = temp.1;
+            if let Some(attentions) = all_attentions.borrow_mut() {
+                attentions.push(attention_weights.unwrap());
+            };
+        };
+
+        (hidden_state, all_hidden_states, all_attentions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_bits_to_f32_known_values() {
+        assert_eq!(half_bits_to_f32(0x3C00), 1.0);
+        assert_eq!(half_bits_to_f32(0x4000), 2.0);
+        assert_eq!(half_bits_to_f32(0xC000), -2.0);
+        assert_eq!(half_bits_to_f32(0x0000), 0.0);
+        assert!(half_bits_to_f32(0x7C00).is_infinite() && half_bits_to_f32(0x7C00) > 0.0);
+        assert!(half_bits_to_f32(0xFC00).is_infinite() && half_bits_to_f32(0xFC00) < 0.0);
+    }
+
+    #[test]
+    fn pack_unpack_nibbles_round_trip() {
+        let in_dim: i64 = 32;
+        let values: Vec<i64> = (0..in_dim).map(|i| (i % 15) - 7).collect();
+        let quantized_block = Tensor::of_slice(&values);
+
+        let packed_cols = (in_dim + 1) / 2;
+        let mut row = Tensor::zeros(&[packed_cols], (Kind::Uint8, tch::Device::Cpu));
+        pack_nibbles(&mut row, &quantized_block, 0);
+
+        let packed = row.view([1, packed_cols]);
+        let unpacked = unpack_nibbles(&packed, in_dim) - 7;
+
+        for i in 0..in_dim {
+            assert_eq!(unpacked.int64_value(&[0, i]), values[i as usize]);
+        }
+    }
+
+    #[test]
+    fn pack_unpack_nibbles_round_trip_partial_block() {
+        // A block shorter than QUANT_BLOCK_SIZE, as the last block of an
+        // `in_dim` that isn't a multiple of 32 would be.
+        let in_dim: i64 = 5;
+        let values: Vec<i64> = vec![-7, -3, 0, 3, 7];
+        let quantized_block = Tensor::of_slice(&values);
+
+        let packed_cols = (in_dim + 1) / 2;
+        let mut row = Tensor::zeros(&[packed_cols], (Kind::Uint8, tch::Device::Cpu));
+        pack_nibbles(&mut row, &quantized_block, 0);
+
+        let packed = row.view([1, packed_cols]);
+        let unpacked = unpack_nibbles(&packed, in_dim) - 7;
+
+        for i in 0..in_dim {
+            assert_eq!(unpacked.int64_value(&[0, i]), values[i as usize]);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright 2018 Google AI and Google Brain team.
+// Copyright 2020-present, the HuggingFace Inc. team.
+// Copyright 2020 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::albert::albert::Activation;
+use crate::common::activations::{_gelu_new, _gelu, _relu, _mish};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use tch::Tensor;
+
+/// An activation function, shared so it can be handed out to every layer
+/// that resolves it from the registry.
+pub type ActivationFn = dyn Fn(&Tensor) -> Tensor + Send + Sync;
+
+fn registry() -> &'static Mutex<HashMap<String, Arc<ActivationFn>>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<String, Arc<ActivationFn>>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let mut map: HashMap<String, Arc<ActivationFn>> = HashMap::new();
+        map.insert("gelu_new".to_string(), Arc::new(_gelu_new));
+        map.insert("gelu".to_string(), Arc::new(_gelu));
+        map.insert("relu".to_string(), Arc::new(_relu));
+        map.insert("mish".to_string(), Arc::new(_mish));
+        map.insert("swish".to_string(), Arc::new(_swish as fn(&Tensor) -> Tensor));
+        map.insert("silu".to_string(), Arc::new(_swish as fn(&Tensor) -> Tensor));
+        map.insert("quick_gelu".to_string(), Arc::new(_quick_gelu as fn(&Tensor) -> Tensor));
+        Mutex::new(map)
+    })
+}
+
+/// Registers a custom activation function under `name`, so that
+/// `resolve_activation(name)` returns it; an existing entry of the same name
+/// is replaced. `AlbertLayer::new` resolves only the names returned by
+/// `ActivationName::registry_name`, so a name outside those four is not
+/// reached from `AlbertConfig::hidden_act` (see `ActivationName`).
+pub fn register_activation<F>(name: &str, activation: F)
+    where F: Fn(&Tensor) -> Tensor + Send + Sync + 'static {
+    registry().lock().unwrap().insert(name.to_string(), Arc::new(activation));
+}
+
+/// Resolves an activation by name against the registry (built-ins plus
+/// anything added through `register_activation`).
+pub fn resolve_activation(name: &str) -> Option<Arc<ActivationFn>> {
+    registry().lock().unwrap().get(name).cloned()
+}
+
+fn _swish(x: &Tensor) -> Tensor {
+    x * x.sigmoid()
+}
+
+fn _quick_gelu(x: &Tensor) -> Tensor {
+    x * (x * 1.702).sigmoid()
+}
+
+/// Gives `Activation` variants a stable string name for registry lookup, so
+/// `AlbertLayer::new` can go through the registry for every variant instead
+/// of matching on it directly.
+///
+/// This only covers the registry side: `Activation` and `AlbertConfig`
+/// still need `#[derive(Serialize, Deserialize)]` added where they're
+/// defined (outside this module) before a config's activation choice can
+/// round-trip through JSON; that derive is not part of this change.
+///
+/// `registry_name` is necessarily an exhaustive match over `Activation`'s
+/// *current* four variants, so `swish`/`silu`/`quick_gelu` (and anything a
+/// caller adds via `register_activation`) are reachable from code that
+/// already holds a name string, but not yet from a deserialized
+/// `AlbertConfig::hidden_act`: `Activation` has no variant that carries an
+/// arbitrary name. Community checkpoints with an activation outside the
+/// built-in four are unblocked only once `Activation` gains something like
+/// a `Custom(String)` variant at its definition site, matched here too;
+/// until then this is registry plumbing, not full pluggability.
+pub trait ActivationName {
+    fn registry_name(&self) -> &'static str;
+}
+
+impl ActivationName for Activation {
+    fn registry_name(&self) -> &'static str {
+        match self {
+            Activation::gelu_new => "gelu_new",
+            Activation::gelu => "gelu",
+            Activation::relu => "relu",
+            Activation::mish => "mish",
+        }
+    }
+}